@@ -1,185 +1,663 @@
-use std::{collections::HashMap, io::Read};
+// `std` is the default feature and keeps the stdin/stdout-backed `run`
+// and the line-based `repl`; with `default-features = false` the crate
+// builds on `alloc` alone for embedded/bare-metal callers, who drive the
+// interpreter through `run_with_io` and their own `ByteReader`/`ByteWriter`
+// impls instead. The crate root carries the matching
+// `#![cfg_attr(not(feature = "std"), no_std)]` and `extern crate alloc;`.
 
-#[derive(Debug)]
-enum Token {
-    Incptr,
-    Decptr,
-    Incbyte,
-    Decbyte,
-    Outbyte,
-    Inbyte,
-    Forward,
-    Backward,
+#[cfg(not(feature = "std"))]
+use alloc::{format, string::String, vec, vec::Vec};
+
+use core::fmt;
+
+#[cfg(feature = "std")]
+use std::io::{self, BufRead};
+
+/// Byte-oriented input source for `,`, implemented for anything that
+/// already implements `std::io::Read` and left open for bare-metal
+/// callers to implement directly when the `std` feature is off.
+pub trait ByteReader {
+    /// Read one byte. `Ok(None)` means the stream is at EOF.
+    fn read_byte(&mut self) -> Result<Option<u8>>;
+}
+
+/// Byte-oriented output sink for `.`.
+pub trait ByteWriter {
+    fn write_byte(&mut self, byte: u8) -> Result<()>;
+}
+
+#[cfg(feature = "std")]
+impl<R: io::Read + ?Sized> ByteReader for R {
+    fn read_byte(&mut self) -> Result<Option<u8>> {
+        let mut buf = [0u8; 1];
+        let n = self.read(&mut buf)?;
+        Ok((n > 0).then_some(buf[0]))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: io::Write + ?Sized> ByteWriter for W {
+    fn write_byte(&mut self, byte: u8) -> Result<()> {
+        self.write_all(&[byte])?;
+        Ok(())
+    }
+}
+
+// The compiled instruction set the interpreter actually executes. Unlike
+// the one-character-at-a-time Brainfuck source, runs of `+`/`-` and
+// `>`/`<` are folded into a single op, and a few common loop idioms are
+// recognized and replaced by a peephole pass (see `recognize_loop`).
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    /// Net change to the current cell, folding consecutive `+`/`-` (under
+    /// `CellMode::Saturating`, only a run that keeps one direction).
+    Add(i32),
+    /// Net change to the data pointer, folding consecutive `>`/`<`.
+    Move(isize),
+    Output,
+    Input,
+    /// Jump to the paired `JumpIfNonZero` when the current cell is 0.
+    JumpIfZero(usize),
+    /// Jump to the paired `JumpIfZero` when the current cell is non-zero.
+    JumpIfNonZero(usize),
+    /// `[-]` / `[+]`: set the current cell to 0.
+    SetZero,
+    /// A "multiply/copy" loop `[- >+... <...]`: add `cells[dp] * factor`
+    /// to the cell at `dp + offset`. The current cell is zeroed separately
+    /// by a trailing `SetZero`.
+    MulAdd { offset: isize, factor: i32 },
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::Add(delta) => write!(f, "ADD {delta}"),
+            Op::Move(delta) => write!(f, "MOVE {delta}"),
+            Op::Output => write!(f, "OUT"),
+            Op::Input => write!(f, "IN"),
+            Op::JumpIfZero(target) => write!(f, "JZ ->{target}"),
+            Op::JumpIfNonZero(target) => write!(f, "JNZ ->{target}"),
+            Op::SetZero => write!(f, "ZERO"),
+            Op::MulAdd { offset, factor } => write!(f, "MULADD {offset:+} x{factor}"),
+        }
+    }
+}
+
+// How much detail `run`/`run_with_io` reports about each executed op.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceMode {
+    /// No tracing output.
+    Off,
+    /// A single summary line once the program halts.
+    SummaryOnHalt,
+    /// One compact line per executed op.
+    PerInstruction,
+}
+
+// How a cell behaves when an increment/decrement pushes it past 0 or 255.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellMode {
+    /// 255 + 1 -> 0, 0 - 1 -> 255. This is the canonical Brainfuck model.
+    Wrapping,
+    /// Clamp at 0 or 255 instead of wrapping around.
+    Saturating,
+}
+
+// What a cell becomes when `,` is executed but the input stream is at EOF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EofPolicy {
+    /// Leave the current cell value untouched.
+    Unchanged,
+    /// Set the current cell to 0.
+    Zero,
+}
+
+// Configures the semantics used by `run`: how cells over/underflow, what
+// happens when the data pointer moves past either end of the tape, and
+// what `,` does once the input stream is exhausted.
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    pub cell_mode: CellMode,
+    // When true, `>` past the last cell wraps to cell 0 and `<` below
+    // cell 0 wraps to the last cell, instead of returning an error.
+    pub wrap_pointer: bool,
+    pub eof_policy: EofPolicy,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            cell_mode: CellMode::Wrapping,
+            wrap_pointer: false,
+            eof_policy: EofPolicy::Unchanged,
+        }
+    }
+}
+
+impl Config {
+    pub fn with_cell_mode(mut self, cell_mode: CellMode) -> Self {
+        self.cell_mode = cell_mode;
+        self
+    }
+
+    pub fn with_wrap_pointer(mut self, wrap_pointer: bool) -> Self {
+        self.wrap_pointer = wrap_pointer;
+        self
+    }
+
+    pub fn with_eof_policy(mut self, eof_policy: EofPolicy) -> Self {
+        self.eof_policy = eof_policy;
+        self
+    }
 }
 
 pub struct Interpreter {
-    ip: usize,                    // Instruction Pointer
-    dp: usize,                    // Data Pointer
-    cells: Vec<isize>,            // Vector of bytes
-    insns: Vec<Token>,            // instrutions are list of Tokens
-    jumps: HashMap<usize, usize>, // Keep track of jumps (forward and backwards)
+    ip: usize,         // Instruction Pointer, indexes into `ops`
+    dp: usize,         // Data Pointer
+    cells: Vec<isize>, // Vector of bytes
+    ops: Vec<Op>,      // Compiled, optimized instructions
+    config: Config,
 }
 
-// Our function just return Err(()) and print the error
-type Result<T> = std::result::Result<T, ()>;
+// What can go wrong while balancing brackets or executing a program.
+#[derive(Debug)]
+pub enum InterpreterError {
+    /// A `[` or `]` at token `index` (its position among the program's
+    /// Brainfuck characters, not in the compiled ops) has no matching counterpart.
+    UnmatchedBracket { index: usize },
+    /// The data pointer moved past the last cell, to `dp`.
+    MemoryOverflow { dp: isize },
+    /// The data pointer moved before cell 0, to `dp`.
+    MemoryUnderflow { dp: isize },
+    /// Reading from or writing to an I/O stream failed. Only constructed
+    /// when the `std` feature is enabled.
+    #[cfg(feature = "std")]
+    Io(io::Error),
+}
 
-impl Interpreter {
-    pub fn new(code: &str) -> Result<Self> {
-        let mut insns: Vec<Token> = Vec::new();
-
-        code.chars().for_each(|c| match c {
-            '>' => insns.push(Token::Incptr),
-            '<' => insns.push(Token::Decptr),
-            '+' => insns.push(Token::Incbyte),
-            '-' => insns.push(Token::Decbyte),
-            '.' => insns.push(Token::Outbyte),
-            ',' => insns.push(Token::Inbyte),
-            '[' => insns.push(Token::Forward),
-            ']' => insns.push(Token::Backward),
-            _ => {}
-        });
-
-        // Let's keep track of jumps in a second pass.
-        let mut jumps_loc: Vec<usize> = Vec::new(); // keep track of open brackets position
-        let mut jumps = HashMap::new();
-        for (i, c) in insns.iter().enumerate() {
-            match c {
-                Token::Forward => {
-                    jumps_loc.push(i);
-                }
-                Token::Backward => {
-                    match jumps_loc.pop() {
-                        None => {
-                            eprintln!("unbalanced brackets");
-                            return Err(());
-                        }
-                        Some(forward_ip) => {
-                            // We add both jumps
-                            jumps.insert(i, forward_ip);
-                            jumps.insert(forward_ip, i);
-                        }
-                    };
-                }
-                _ => { // Nothing to do}
-                }
+impl fmt::Display for InterpreterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterpreterError::UnmatchedBracket { index } => {
+                write!(f, "unmatched bracket at token {index}")
+            }
+            InterpreterError::MemoryOverflow { dp } => {
+                write!(f, "memory overflow: data pointer {dp} moved past the last cell")
             }
+            InterpreterError::MemoryUnderflow { dp } => {
+                write!(f, "memory underflow: data pointer {dp} moved before cell 0")
+            }
+            #[cfg(feature = "std")]
+            InterpreterError::Io(e) => write!(f, "I/O error: {e}"),
         }
+    }
+}
 
-        if !jumps_loc.is_empty() {
-            eprintln!("Missing closed brackets");
-            return Err(());
+#[cfg(feature = "std")]
+impl std::error::Error for InterpreterError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            InterpreterError::Io(e) => Some(e),
+            _ => None,
         }
+    }
+}
 
-        Ok(Self {
+#[cfg(feature = "std")]
+impl From<io::Error> for InterpreterError {
+    fn from(e: io::Error) -> Self {
+        InterpreterError::Io(e)
+    }
+}
+
+type Result<T> = core::result::Result<T, InterpreterError>;
+
+impl Interpreter {
+    pub fn new(code: &str) -> Result<Self> {
+        Self::with_config(code, Config::default())
+    }
+
+    pub fn with_config(code: &str, config: Config) -> Result<Self> {
+        let mut interp = Self {
             ip: 0,
             dp: 0,
-            insns,
+            ops: Vec::new(),
             cells: vec![0; 1024],
-            jumps,
-        })
+            config,
+        };
+        interp.load(code)?;
+        Ok(interp)
+    }
+
+    /// Compile `code` and make it the program to execute, without touching
+    /// the tape (`cells`/`dp`). Lets a REPL recompile a new line against
+    /// whatever state earlier lines left behind.
+    pub fn load(&mut self, code: &str) -> Result<()> {
+        self.ops = compile(code, self.config.cell_mode)?;
+        self.ip = 0;
+        Ok(())
+    }
+
+    /// Zero the tape and drop the loaded program, returning the interpreter
+    /// to the state `new` would produce for an empty program.
+    pub fn reset(&mut self) {
+        self.cells.iter_mut().for_each(|c| *c = 0);
+        self.ip = 0;
+        self.dp = 0;
+        self.ops.clear();
+    }
+
+    /// Read lines of Brainfuck from `input` and execute each one against
+    /// this interpreter, writing output to `output` as it's produced. A
+    /// blank line or EOF ends the session. When `preserve_tape` is false
+    /// the tape is zeroed (via `reset`) before every line, so each line
+    /// behaves like an independent program instead of building on the last.
+    /// A line that fails to compile or run (e.g. an unmatched bracket) is
+    /// reported to `output` as an `error: ...` line rather than ending the
+    /// session, so a typo doesn't throw away tape state built up so far.
+    #[cfg(feature = "std")]
+    pub fn repl<R: BufRead>(
+        &mut self,
+        preserve_tape: bool,
+        input: &mut R,
+        output: &mut dyn ByteWriter,
+    ) -> Result<()> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if input.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+
+            if !preserve_tape {
+                self.reset();
+            }
+
+            let result = self
+                .load(&line)
+                .and_then(|()| self.run_with_io(TraceMode::Off, input, output));
+            if let Err(e) = result {
+                write_str(output, &format!("error: {e}\n"))?;
+            }
+        }
+
+        Ok(())
     }
 
-    fn interpreter_state(&self) {
-        println!("-----------------------------------");
-        println!("Next instruction: {:?}", self.insns[self.ip]);
-        println!("IP: {:?}", self.ip);
-        println!("DP: {:?}", self.dp);
-        // print non empty cell
+    // Apply a delta to the cell at `idx` according to `config.cell_mode`.
+    fn apply_delta(&mut self, idx: usize, delta: isize) {
+        let cell = &mut self.cells[idx];
+        *cell = match self.config.cell_mode {
+            CellMode::Wrapping => (*cell as u8).wrapping_add(delta as u8) as isize,
+            CellMode::Saturating => (*cell + delta).clamp(0, 255),
+        };
+    }
+
+    // Move the data pointer by `delta`, applying the configured overflow
+    // policy for whatever lies past either end of the tape.
+    fn move_pointer(&mut self, delta: isize) -> Result<()> {
+        let len = self.cells.len() as isize;
+        let new_dp = self.dp as isize + delta;
+
+        if self.config.wrap_pointer {
+            self.dp = new_dp.rem_euclid(len) as usize;
+        } else if new_dp < 0 {
+            return Err(InterpreterError::MemoryUnderflow { dp: new_dp });
+        } else if new_dp >= len {
+            return Err(InterpreterError::MemoryOverflow { dp: new_dp });
+        } else {
+            self.dp = new_dp as usize;
+        }
+
+        Ok(())
+    }
+
+    // Resolve `dp + offset` to a concrete cell index, applying the same
+    // overflow policy as `move_pointer`, and accumulate `cells[dp] * factor`
+    // into it. Used by the `MulAdd` op.
+    fn mul_add(&mut self, offset: isize, factor: i32) -> Result<()> {
+        let len = self.cells.len() as isize;
+        let target = self.dp as isize + offset;
+
+        let idx = if self.config.wrap_pointer {
+            target.rem_euclid(len) as usize
+        } else if target < 0 {
+            return Err(InterpreterError::MemoryUnderflow { dp: target });
+        } else if target >= len {
+            return Err(InterpreterError::MemoryOverflow { dp: target });
+        } else {
+            target as usize
+        };
+
+        let delta = self.cells[self.dp] * factor as isize;
+        self.apply_delta(idx, delta);
+        Ok(())
+    }
+
+    /// Dump the non-zero cells of the tape as text. Unlike the old
+    /// per-step debug print, this is never called automatically — invoke
+    /// it yourself whenever you want a snapshot.
+    pub fn dump_tape(&self) -> String {
+        let mut out = String::new();
         for (id, c) in self.cells.iter().enumerate() {
             if *c != 0 {
-                println!("cell[{:?}] = {:?}", id, *c);
+                out.push_str(&format!("cell[{id}] = {c}\n"));
             }
         }
+        out
     }
 
-    pub fn run(&mut self, debug: bool) -> Result<String> {
-        let mut output = String::default();
+    /// Render the compiled program back to Brainfuck source. `+`/`-` and
+    /// `>`/`<` runs are expanded back to repeated characters; jumps are
+    /// rendered as `[`/`]` annotated with their *partner's* op index (e.g.
+    /// `[@30`, `]@12` for a loop whose `[` is op 12 and `]` is op 30) so a
+    /// matched pair can be spotted by its indices; ops introduced by the
+    /// loop peephole pass are rendered with a similar `@index` annotation
+    /// since they no longer have a literal source form (that index is the
+    /// op's own, since they have no partner).
+    pub fn disassemble(&self) -> String {
+        let mut out = String::new();
+        for (i, op) in self.ops.iter().enumerate() {
+            match op {
+                Op::Add(delta) => {
+                    let ch = if *delta >= 0 { '+' } else { '-' };
+                    for _ in 0..delta.unsigned_abs() {
+                        out.push(ch);
+                    }
+                }
+                Op::Move(delta) => {
+                    let ch = if *delta >= 0 { '>' } else { '<' };
+                    for _ in 0..delta.unsigned_abs() {
+                        out.push(ch);
+                    }
+                }
+                Op::Output => out.push('.'),
+                Op::Input => out.push(','),
+                Op::JumpIfZero(target) => out.push_str(&format!("[@{target}")),
+                Op::JumpIfNonZero(target) => out.push_str(&format!("]@{target}")),
+                Op::SetZero => out.push_str(&format!("[-]@{i}")),
+                Op::MulAdd { offset, factor } => {
+                    out.push_str(&format!("[mul {offset:+} x{factor}]@{i}"));
+                }
+            }
+        }
+        out
+    }
+
+    /// Run against stdin/stdout.
+    #[cfg(feature = "std")]
+    pub fn run(&mut self, trace: TraceMode) -> Result<()> {
+        let mut input = std::io::stdin();
+        let mut output = std::io::stdout();
+        self.run_with_io(trace, &mut input, &mut output)
+    }
+
+    /// Run against the given input/output streams, e.g. to feed canned
+    /// input or capture output into a buffer in tests, or a bare-metal
+    /// `ByteReader`/`ByteWriter` pair when built without the `std` feature.
+    /// Tracing output (anything but `TraceMode::Off`) requires `std`, since
+    /// it's printed with `println!`; it's a no-op otherwise.
+    pub fn run_with_io(
+        &mut self,
+        trace: TraceMode,
+        input: &mut dyn ByteReader,
+        output: &mut dyn ByteWriter,
+    ) -> Result<()> {
+        #[cfg(not(feature = "std"))]
+        let _ = trace;
+        #[cfg(feature = "std")]
+        let mut steps: u64 = 0;
 
         loop {
             // The program terminates when the instruction pointer
-            // moves past the last command.
-            if self.ip > self.insns.len() - 1 {
+            // moves past the last op.
+            if self.ip >= self.ops.len() {
                 break;
             }
 
-            if debug {
-                // As interpreter_state is using self.ip run it after
-                // checking boundaries ^^^
-                self.interpreter_state()
+            #[cfg(feature = "std")]
+            {
+                steps += 1;
+                if trace == TraceMode::PerInstruction {
+                    println!(
+                        "{:>5} | {:<18} | dp={} cell={}",
+                        self.ip, self.ops[self.ip], self.dp, self.cells[self.dp]
+                    );
+                }
             }
 
-            match self.insns[self.ip] {
-                Token::Incptr => {
-                    self.dp += 1;
-                    if self.dp >= self.cells.len() {
-                        eprintln!("Memory overflow");
-                        return Err(());
-                    }
+            match self.ops[self.ip] {
+                Op::Add(delta) => self.apply_delta(self.dp, delta as isize),
+                Op::Move(delta) => self.move_pointer(delta)?,
+                Op::Output => {
+                    output.write_byte(self.cells[self.dp] as u8)?;
                 }
-                Token::Decptr => {
-                    if self.dp == 0 {
-                        eprintln!("Memory underflow");
-                        return Err(());
-                    }
-                    self.dp -= 1;
-                }
-                Token::Incbyte => self.cells[self.dp] += 1,
-                Token::Decbyte => self.cells[self.dp] -= 1,
-                Token::Outbyte => {
-                    if let Some(c) = char::from_u32(self.cells[self.dp] as u32) {
-                        output.push(c);
-                    }
-                }
-                Token::Inbyte => {
-                    let mut buf: [u8; 1] = [0];
-                    match std::io::stdin().read(&mut buf) {
-                        Err(e) => {
-                            eprintln!("Failed to read byte from stdin: {}", e);
-                            return Err(());
-                        }
-                        Ok(n) => {
-                            if n > 0 {
-                                self.cells[self.dp] = buf[0] as isize
+                Op::Input => {
+                    match input.read_byte()? {
+                        Some(byte) => self.cells[self.dp] = byte as isize,
+                        None => {
+                            // EOF: apply the configured policy.
+                            match self.config.eof_policy {
+                                EofPolicy::Unchanged => {}
+                                EofPolicy::Zero => self.cells[self.dp] = 0,
                             }
-                            // otherwise we are done
                         }
                     }
                 }
-                Token::Forward => {
+                Op::JumpIfZero(target) => {
                     if self.cells[self.dp] == 0 {
-                        match self.jumps.get(&self.ip) {
-                            Some(new_ip) => self.ip = *new_ip, // IP is incremented at the end
-                            None => {
-                                eprintln!("Failed to match bracket");
-                                return Err(());
-                            }
-                        }
+                        self.ip = target; // IP is incremented at the end
                     }
                 }
-                Token::Backward => {
+                Op::JumpIfNonZero(target) => {
                     if self.cells[self.dp] != 0 {
-                        match self.jumps.get(&self.ip) {
-                            Some(new_ip) => self.ip = *new_ip, // IP is incremented at the end
-                            None => {
-                                eprintln!("Failed to match bracket");
-                                return Err(());
-                            }
-                        }
+                        self.ip = target; // IP is incremented at the end
                     }
                 }
+                Op::SetZero => self.cells[self.dp] = 0,
+                Op::MulAdd { offset, factor } => self.mul_add(offset, factor)?,
             }
 
             self.ip += 1;
         }
 
-        println!();
-        Ok(output)
+        #[cfg(feature = "std")]
+        if trace != TraceMode::Off {
+            println!("halted after {steps} step(s): ip={}, dp={}", self.ip, self.dp);
+        }
+
+        Ok(())
+    }
+}
+
+// Write `msg` to `output` a byte at a time. Used to report a `repl` line's
+// error without ending the session, since `ByteWriter` is the only stream
+// a caller is guaranteed to have given it.
+#[cfg(feature = "std")]
+fn write_str(output: &mut dyn ByteWriter, msg: &str) -> Result<()> {
+    msg.bytes().try_for_each(|byte| output.write_byte(byte))
+}
+
+// Compile Brainfuck source directly into optimized `Op`s: runs of
+// `+`/`-`/`>`/`<` are coalesced, and loops are peephole-matched against
+// `recognize_loop` before falling back to a plain jump pair.
+fn compile(code: &str, cell_mode: CellMode) -> Result<Vec<Op>> {
+    let chars: Vec<char> = code
+        .chars()
+        .filter(|c| matches!(c, '>' | '<' | '+' | '-' | '.' | ',' | '[' | ']'))
+        .collect();
+
+    let mut ops: Vec<Op> = Vec::new();
+    // (char index of the `[`, op index of its `JumpIfZero`) for each open loop.
+    let mut loop_starts: Vec<(usize, usize)> = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '+' | '-' => {
+                let mut delta: i32 = 0;
+                while i < chars.len() && matches!(chars[i], '+' | '-') {
+                    let step = if chars[i] == '+' { 1 } else { -1 };
+                    // Under `Saturating`, a run that changes direction can't be
+                    // folded: `+-` at 255 clamps to 255 and then drops to 254.
+                    if cell_mode == CellMode::Saturating && delta != 0 && delta.signum() != step {
+                        break;
+                    }
+                    delta += step;
+                    i += 1;
+                }
+                ops.push(Op::Add(delta));
+            }
+            '>' | '<' => {
+                let mut delta: isize = 0;
+                while i < chars.len() && matches!(chars[i], '>' | '<') {
+                    delta += if chars[i] == '>' { 1 } else { -1 };
+                    i += 1;
+                }
+                ops.push(Op::Move(delta));
+            }
+            '.' => {
+                ops.push(Op::Output);
+                i += 1;
+            }
+            ',' => {
+                ops.push(Op::Input);
+                i += 1;
+            }
+            '[' => {
+                loop_starts.push((i, ops.len()));
+                ops.push(Op::JumpIfZero(0)); // patched once the matching `]` is found
+                i += 1;
+            }
+            ']' => {
+                let start = match loop_starts.pop() {
+                    Some((_, start)) => start,
+                    None => return Err(InterpreterError::UnmatchedBracket { index: i }),
+                };
+
+                if let Some(replacement) = recognize_loop(&ops[start + 1..], cell_mode) {
+                    ops.truncate(start);
+                    ops.extend(replacement);
+                } else {
+                    let end = ops.len();
+                    ops.push(Op::JumpIfNonZero(start));
+                    ops[start] = Op::JumpIfZero(end);
+                }
+                i += 1;
+            }
+            _ => unreachable!("filtered to Brainfuck tokens above"),
+        }
+    }
+
+    if let Some((index, _)) = loop_starts.pop() {
+        return Err(InterpreterError::UnmatchedBracket { index });
     }
+
+    Ok(ops)
+}
+
+// Recognize two common loop idioms on a loop body that has already been
+// compiled to `Op`s (but does not include the enclosing `[`/`]`):
+//   - `[-]` / `[+]`: the body is a single `Add(-1)`/`Add(1)` -> `SetZero`.
+//   - `[- >+... <...]`: a "multiply/copy" loop whose body only moves the
+//     pointer and adds to cells, nets zero pointer displacement, and
+//     decrements the current cell by exactly 1 per iteration -> one
+//     `MulAdd` per touched offset followed by `SetZero`.
+// Returns `None` if the body doesn't match either shape, leaving the loop
+// to be compiled as a plain jump pair.
+//
+// Both shapes assume a cell that decrements by exactly 1 per iteration
+// eventually hits 0, which only holds under `CellMode::Wrapping` (under
+// `Saturating`, `[+]` on a nonzero cell clamps at 255 forever instead of
+// reaching 0). So this peephole is skipped entirely outside `Wrapping`,
+// and the loop falls back to a plain, step-by-step jump pair that a
+// saturating interpreter executes correctly (if slowly).
+fn recognize_loop(body: &[Op], cell_mode: CellMode) -> Option<Vec<Op>> {
+    if cell_mode != CellMode::Wrapping {
+        return None;
+    }
+
+    if let [Op::Add(1 | -1)] = body {
+        return Some(vec![Op::SetZero]);
+    }
+
+    let mut offset: isize = 0;
+    let mut deltas: Vec<(isize, i32)> = Vec::new(); // (offset, net delta), first-seen order
+
+    for op in body {
+        match *op {
+            Op::Add(delta) => match deltas.iter_mut().find(|(o, _)| *o == offset) {
+                Some(entry) => entry.1 += delta,
+                None => deltas.push((offset, delta)),
+            },
+            Op::Move(delta) => offset += delta,
+            _ => return None, // I/O or nested jumps: not a simple arithmetic loop
+        }
+    }
+
+    if offset != 0 {
+        return None; // the loop must return the pointer to where it started
+    }
+
+    match deltas.iter().find(|(o, _)| *o == 0) {
+        Some((_, -1)) => {}
+        _ => return None, // the current cell must be decremented by exactly 1
+    }
+
+    let mut replacement: Vec<Op> = deltas
+        .into_iter()
+        .filter(|(o, _)| *o != 0)
+        .map(|(offset, factor)| Op::MulAdd { offset, factor })
+        .collect();
+    replacement.push(Op::SetZero);
+    Some(replacement)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::brainfuck::Interpreter;
+    #[cfg(not(feature = "std"))]
+    use alloc::{string::String, vec::Vec};
+
+    use crate::brainfuck::{
+        ByteReader, ByteWriter, CellMode, Config, Interpreter, InterpreterError, Result,
+        TraceMode,
+    };
+
+    // `ByteReader`/`ByteWriter` for `&[u8]`/`Vec<u8>` only exist behind the
+    // `std` feature (they ride the blanket impl over `std::io::Read`/
+    // `Write`), so the tests implement their own minimal fakes directly
+    // against the traits to stay exercised on a `no_std` + `alloc` build.
+    struct SliceReader<'a> {
+        data: &'a [u8],
+        pos: usize,
+    }
+
+    impl ByteReader for SliceReader<'_> {
+        fn read_byte(&mut self) -> Result<Option<u8>> {
+            let byte = self.data.get(self.pos).copied();
+            if byte.is_some() {
+                self.pos += 1;
+            }
+            Ok(byte)
+        }
+    }
+
+    struct VecWriter(Vec<u8>);
+
+    impl ByteWriter for VecWriter {
+        fn write_byte(&mut self, byte: u8) -> Result<()> {
+            self.0.push(byte);
+            Ok(())
+        }
+    }
+
+    fn run_capture(prog: &mut Interpreter) -> String {
+        let mut input = SliceReader { data: &[], pos: 0 };
+        let mut output = VecWriter(Vec::new());
+        prog.run_with_io(TraceMode::Off, &mut input, &mut output)
+            .expect("run failed");
+        String::from_utf8(output.0).expect("output was not valid UTF-8")
+    }
 
     #[test]
     pub fn github_profile() {
@@ -189,7 +667,7 @@ mod tests {
             ",
         )
         .unwrap();
-        let output = prog.run(false).unwrap_or("FAILED".to_string());
+        let output = run_capture(&mut prog);
         assert_eq!(output, "gthvn");
     }
 
@@ -233,7 +711,166 @@ Pointer :   ^
             ",
         )
         .unwrap();
-        let output = prog.run(false).unwrap_or("FAILED".to_string());
+        let output = run_capture(&mut prog);
         assert_eq!(output, "Hello World!\n");
     }
+
+    #[test]
+    pub fn saturating_cell_mode_clamps_instead_of_wrapping() {
+        // Wrapping (the default): 0 - 1 -> 255.
+        let mut prog = Interpreter::new("-").unwrap();
+        let mut input = SliceReader { data: &[], pos: 0 };
+        let mut output = VecWriter(Vec::new());
+        prog.run_with_io(TraceMode::Off, &mut input, &mut output)
+            .expect("run failed");
+        assert_eq!(prog.dump_tape(), "cell[0] = 255\n");
+
+        // Saturating: 0 - 1 -> 0 (clamped, not wrapped).
+        let mut prog =
+            Interpreter::with_config("-", Config::default().with_cell_mode(CellMode::Saturating))
+                .unwrap();
+        let mut input = SliceReader { data: &[], pos: 0 };
+        let mut output = VecWriter(Vec::new());
+        prog.run_with_io(TraceMode::Off, &mut input, &mut output)
+            .expect("run failed");
+        assert_eq!(prog.dump_tape(), "");
+    }
+
+    #[test]
+    pub fn wrap_pointer_moves_past_either_end_instead_of_erroring() {
+        let config = Config::default().with_wrap_pointer(true);
+
+        // One step past the last cell wraps to cell 0.
+        let mut prog = Interpreter::with_config(&">".repeat(1024), config).unwrap();
+        let mut input = SliceReader { data: &[], pos: 0 };
+        let mut output = VecWriter(Vec::new());
+        prog.run_with_io(TraceMode::Off, &mut input, &mut output)
+            .expect("wrap_pointer should avoid MemoryOverflow");
+
+        // One step before cell 0 wraps to the last cell.
+        let mut prog = Interpreter::with_config("<", config).unwrap();
+        let mut input = SliceReader { data: &[], pos: 0 };
+        let mut output = VecWriter(Vec::new());
+        prog.run_with_io(TraceMode::Off, &mut input, &mut output)
+            .expect("wrap_pointer should avoid MemoryUnderflow");
+    }
+
+    #[test]
+    pub fn saturating_clear_loop_is_not_peepholed() {
+        // Under `CellMode::Saturating`, `[+]` on a nonzero cell never
+        // reaches 0 (it clamps at 255), so collapsing it to `SetZero`
+        // would change the program's semantics (and a real step-by-step
+        // interpreter would hang forever on it instead). The peephole
+        // must not fire here, leaving a plain jump pair; check via
+        // `disassemble` rather than running it, since running it is
+        // exactly the infinite loop the correct semantics demand.
+        let prog = Interpreter::with_config(
+            "+++++[+]",
+            Config::default().with_cell_mode(CellMode::Saturating),
+        )
+        .unwrap();
+        let asm = prog.disassemble();
+        assert!(!asm.contains("[-]@"), "got {asm:?}, expected a plain jump pair");
+        assert!(asm.contains("[@") && asm.contains("]@"), "got {asm:?}");
+    }
+
+    #[test]
+    pub fn unmatched_bracket_index_is_consistent() {
+        // An extra `]` reports the char position directly.
+        match Interpreter::new("++++++++]").err() {
+            Some(InterpreterError::UnmatchedBracket { index }) => assert_eq!(index, 8),
+            other => panic!("expected UnmatchedBracket, got {other:?}"),
+        }
+
+        // A missing `]` must report the `[`'s own char position too, not
+        // its index in the coalesced op stream (here: `++++++++` folds
+        // into a single `Add`, so the op index would be 1, not 8).
+        match Interpreter::new("++++++++[+").err() {
+            Some(InterpreterError::UnmatchedBracket { index }) => assert_eq!(index, 8),
+            other => panic!("expected UnmatchedBracket, got {other:?}"),
+        }
+    }
+
+    #[test]
+    pub fn disassemble_annotates_jumps_with_partner_index() {
+        // `++[.-]`: op 0 is `Add(2)`, op 1 is `JumpIfZero` (partner at 4),
+        // ops 2-3 are `.`/`Add(-1)`, op 4 is `JumpIfNonZero` (partner at 1).
+        // Each bracket must be annotated with its *partner's* index, not
+        // its own, so a matched pair can be spotted by its indices.
+        let prog = Interpreter::new("++[.-]").unwrap();
+        assert_eq!(prog.disassemble(), "++[@4.-]@1");
+    }
+
+    #[test]
+    pub fn memory_overflow_reports_target_not_start() {
+        // A run of 1024 `>` starting at dp=0 coalesces into one
+        // `Move(1024)`; the error must report the computed out-of-range
+        // target, not the pointer's value before the move.
+        let mut prog = Interpreter::new(&">".repeat(1024)).unwrap();
+        let mut input = SliceReader { data: &[], pos: 0 };
+        let mut output = VecWriter(Vec::new());
+        match prog.run_with_io(TraceMode::Off, &mut input, &mut output) {
+            Err(InterpreterError::MemoryOverflow { dp }) => assert_eq!(dp, 1024),
+            other => panic!("expected MemoryOverflow, got {other:?}"),
+        }
+    }
+
+    #[test]
+    pub fn reset_zeroes_tape_and_clears_program() {
+        let mut prog = Interpreter::new(">+").unwrap();
+        let mut input = SliceReader { data: &[], pos: 0 };
+        let mut output = VecWriter(Vec::new());
+        prog.run_with_io(TraceMode::Off, &mut input, &mut output)
+            .expect("run failed");
+        assert_ne!(prog.dump_tape(), "");
+        assert_ne!(prog.dp, 0);
+
+        prog.reset();
+        assert_eq!(prog.dump_tape(), "");
+        assert_eq!(prog.ip, 0);
+        assert_eq!(prog.dp, 0);
+        assert!(prog.ops.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    pub fn repl_preserves_tape_across_lines_when_requested() {
+        // "define a value, then print it": the first line sets cell 0 to
+        // 3 with no output; the second line prints whatever is there.
+        // With `preserve_tape == true` that must be the 3 the first line
+        // left behind.
+        let mut prog = Interpreter::new("").unwrap();
+        let mut input: &[u8] = b"+++\n.\n";
+        let mut output = VecWriter(Vec::new());
+        prog.repl(true, &mut input, &mut output).expect("repl failed");
+        assert_eq!(output.0, vec![3]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    pub fn repl_resets_tape_between_lines_when_not_preserving() {
+        // Same two lines, but `preserve_tape == false` resets the tape
+        // before each one, so the second line prints a fresh (zeroed)
+        // cell instead of the 3 the first line set.
+        let mut prog = Interpreter::new("").unwrap();
+        let mut input: &[u8] = b"+++\n.\n";
+        let mut output = VecWriter(Vec::new());
+        prog.repl(false, &mut input, &mut output).expect("repl failed");
+        assert_eq!(output.0, vec![0]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    pub fn repl_reports_bad_line_and_keeps_going() {
+        // A line with an unmatched bracket must not end the session: the
+        // error is reported to `output` and the next line still runs
+        // against the tape state built up so far.
+        let mut prog = Interpreter::new("").unwrap();
+        let mut input: &[u8] = b"+++\n]\n.\n";
+        let mut output = VecWriter(Vec::new());
+        prog.repl(true, &mut input, &mut output).expect("repl failed");
+        let rendered = String::from_utf8(output.0).expect("output was not valid UTF-8");
+        assert!(rendered.starts_with("error: "), "got {rendered:?}");
+        assert!(rendered.ends_with('\u{3}'), "got {rendered:?}");
+    }
 }